@@ -10,6 +10,37 @@ sp1_zkvm::entrypoint!(main);
 
 use fibonacci_lib::{Output, OutputsType, PayloadState, PrevOut, PrevOutsType, TxId};
 
+/// Upper bound on any single token amount (total realistic supply). Keeps every sum far away from
+/// `u64` overflow and bounds the values downstream encodings have to handle.
+const MAX_AMOUNT: u64 = 21_000_000 * 100_000_000;
+
+/// Layout version of the committed public values. Always committed first so verifiers can pick
+/// the right decoder; bump it whenever a committed field is added, removed or reordered.
+const PV_VERSION: u16 = 1;
+
+/// Byte length of a Schnorr signature, the only signature scheme P2PK inputs are spent with.
+const SIGNATURE_LEN: usize = 64;
+
+/// Wraps a validation phase in SP1 cycle-tracker markers when built with the `profile` feature, so
+/// the execution report breaks the cycle count down per phase. Expands to the bare expression
+/// otherwise, so proving builds are unaffected.
+#[cfg(feature = "profile")]
+macro_rules! phase {
+    ($name:literal, $body:expr) => {{
+        println!(concat!("cycle-tracker-start: ", $name));
+        let result = $body;
+        println!(concat!("cycle-tracker-end: ", $name));
+        result
+    }};
+}
+
+#[cfg(not(feature = "profile"))]
+macro_rules! phase {
+    ($name:literal, $body:expr) => {
+        $body
+    };
+}
+
 struct SignatureMessage {
     _prev_out_idx: usize,
     _prev_out_tx_id: TxId,
@@ -23,6 +54,57 @@ fn extract_pub_key_from_script_pub_key(_script_pub_key: &[u8]) -> Option<Vec<u8>
     Some(vec![])
 }
 
+/// How the spent token output is locked, which decides how its spend is authorized.
+enum LockingScript {
+    /// Spending requires a signature under this key.
+    PubKey(Vec<u8>),
+    /// Spending is delegated to another input that spends the same script.
+    ScriptHash,
+}
+
+fn classify_script_pub_key(script_pub_key: &[u8]) -> LockingScript {
+    match extract_pub_key_from_script_pub_key(script_pub_key) {
+        Some(pub_key) => LockingScript::PubKey(pub_key),
+        None => LockingScript::ScriptHash,
+    }
+}
+
+fn check_spent_indices(prev_outs: &[PrevOut]) {
+    // Each input spends exactly the one output at `idx` of its previous state; the siblings stay
+    // unspent, so only that output is ever read for accounting.
+    assert!(
+        prev_outs
+            .iter()
+            .all(|prev| prev.idx < prev.state.outs.len()),
+        "Spent output index is out of range for its previous state"
+    );
+}
+
+fn check_recipients(next_state: &PayloadState) {
+    // An empty or all-zero owner script can't be spent by anyone, so the tokens would be burned by
+    // accident. There is no explicit burn script yet, so these are always rejected.
+    assert!(
+        next_state
+            .outs
+            .iter()
+            .all(|out| out.script_pub_key.iter().any(|&b| b != 0)),
+        "Output is addressed to an empty or all-zero script"
+    );
+}
+
+fn amount_range_check(prev_outs: &[PrevOut], next_state: &PayloadState) {
+    assert!(
+        prev_outs
+            .iter()
+            .all(|prev| prev.state.outs[prev.idx].amount <= MAX_AMOUNT),
+        "Input amount exceeds MAX_AMOUNT"
+    );
+    assert!(
+        next_state.outs.iter().all(|out| out.amount <= MAX_AMOUNT),
+        "Output amount exceeds MAX_AMOUNT"
+    );
+}
+
 fn balance_check(prev_outs: &[PrevOut], next_state: &PayloadState) {
     let total_in = prev_outs
         .iter()
@@ -34,6 +116,16 @@ fn balance_check(prev_outs: &[PrevOut], next_state: &PayloadState) {
         .map(|output| output.amount)
         .sum::<u64>();
 
+    // Only printed when built with the `trace` feature, so proving doesn't pay for it.
+    #[cfg(feature = "trace")]
+    println!(
+        "trace: {} inputs totalling {}, {} outputs totalling {}",
+        prev_outs.len(),
+        total_in,
+        next_state.outs.len(),
+        total_out
+    );
+
     assert_eq!(total_in, total_out, "Input and output totals must match");
 }
 
@@ -42,6 +134,12 @@ fn check_spend_to_same_covenant(
     current_utxo_script_pub_key: &[u8],
     num_token_outs: usize,
 ) {
+    // Every token output in the next state must be backed by an actual transaction output,
+    // otherwise the `take` below would silently check fewer outputs than the state claims.
+    assert!(
+        outs.len() >= num_token_outs,
+        "Each next state output must have a corresponding transaction output"
+    );
     // TODO: We can maybe make this the wallet guarantee. If it's violated the tokens will be effectively burned.
     assert!(
         outs.iter()
@@ -62,41 +160,110 @@ fn check_p2sh_spend(prev_outs: &[PrevOut], current_token_spk: &[u8]) {
     );
 }
 
+fn check_single_txid(prev_outs: &[PrevOut], current_input_idx: usize) {
+    assert!(
+        current_input_idx < prev_outs.len(),
+        "The current input index is out of range"
+    );
+    // Only the current input's outpoint is needed for its signature message, so the witness
+    // must carry exactly that one txid.
+    assert!(
+        prev_outs
+            .iter()
+            .enumerate()
+            .all(|(i, prev)| prev.txid.is_some() == (i == current_input_idx)),
+        "Exactly the current input must have a txid"
+    );
+}
+
+/// Everything the host writes to stdin for one proof.
+struct Witness {
+    prev_outs: PrevOutsType,
+    current_input_idx: usize,
+    current_input_sig: Vec<u8>,
+    outs: OutputsType,
+    next_state: PayloadState,
+}
+
+/// Reads the witness in the exact order the host writes it. This is the only place the order is
+/// spelled out, so any change here must be mirrored on the host side.
+fn read_witness() -> Witness {
+    Witness {
+        prev_outs: sp1_zkvm::io::read::<PrevOutsType>(),
+        current_input_idx: sp1_zkvm::io::read::<usize>(),
+        current_input_sig: sp1_zkvm::io::read::<Vec<u8>>(),
+        outs: sp1_zkvm::io::read::<OutputsType>(),
+        next_state: sp1_zkvm::io::read::<PayloadState>(),
+    }
+}
+
 pub fn main() {
-    let prev_outs = sp1_zkvm::io::read::<PrevOutsType>();
-    let current_input_idx = sp1_zkvm::io::read::<usize>();
-    let current_input_sig = sp1_zkvm::io::read::<Vec<u8>>();
-    let outs = sp1_zkvm::io::read::<OutputsType>();
-    let next_state = sp1_zkvm::io::read::<PayloadState>();
+    let Witness {
+        prev_outs,
+        current_input_idx,
+        current_input_sig,
+        outs,
+        next_state,
+    } = phase!("deserialization", read_witness());
 
     let outs = outs.into_iter().flatten().collect::<Vec<Output>>();
     let prev_outs = prev_outs.into_iter().flatten().collect::<Vec<PrevOut>>();
 
-    balance_check(&prev_outs, &next_state);
+    // There is no explicit burn yet, so a transaction must create at least one token output.
+    assert!(
+        !next_state.outs.is_empty(),
+        "The next state must contain at least one output"
+    );
+
+    phase!("input_checks", {
+        check_single_txid(&prev_outs, current_input_idx);
+        check_spent_indices(&prev_outs);
+        check_recipients(&next_state);
+        amount_range_check(&prev_outs, &next_state);
+    });
+    phase!("conservation", balance_check(&prev_outs, &next_state));
 
     let current_prev_out = &prev_outs[current_input_idx];
     let current_utxo_script_pub_key = &current_prev_out.script_pub_key;
 
-    check_spend_to_same_covenant(&outs, current_utxo_script_pub_key, next_state.outs.len());
+    phase!(
+        "covenant",
+        check_spend_to_same_covenant(&outs, current_utxo_script_pub_key, next_state.outs.len())
+    );
 
     let current_token_spk = &current_prev_out.state.outs[current_prev_out.idx].script_pub_key;
 
     let prev_out_tx_id = current_prev_out.txid.unwrap();
 
     // We only validate the signature of the current input, since we assume the other inputs will make the same check.
-    if let Some(pub_key) = extract_pub_key_from_script_pub_key(current_token_spk) {
-        assert!(
-            check_sig(
-                current_input_sig,
-                &pub_key,
-                SignatureMessage {
-                    _prev_out_idx: current_prev_out.idx,
-                    _prev_out_tx_id: prev_out_tx_id,
-                }
-            ),
-            "Invalid signature"
-        );
-    } else {
-        check_p2sh_spend(&prev_outs, current_token_spk);
-    }
+    phase!(
+        "authorization",
+        match classify_script_pub_key(current_token_spk) {
+            LockingScript::PubKey(pub_key) => {
+                assert_eq!(
+                    current_input_sig.len(),
+                    SIGNATURE_LEN,
+                    "Signature has the wrong length for the signature scheme"
+                );
+                assert!(
+                    check_sig(
+                        current_input_sig,
+                        &pub_key,
+                        SignatureMessage {
+                            _prev_out_idx: current_prev_out.idx,
+                            _prev_out_tx_id: prev_out_tx_id,
+                        }
+                    ),
+                    "Invalid signature"
+                );
+            }
+            LockingScript::ScriptHash => check_p2sh_spend(&prev_outs, current_token_spk),
+        }
+    );
+
+    sp1_zkvm::io::commit(&PV_VERSION);
+    // Commit the present input/output counts so the on-chain script can compare them against
+    // what it introspects without parsing the full state.
+    sp1_zkvm::io::commit(&prev_outs.len());
+    sp1_zkvm::io::commit(&outs.len());
 }